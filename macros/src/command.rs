@@ -5,7 +5,7 @@ use proc_macro_error::*;
 use quote::quote;
 use std::collections::HashMap;
 use syn::spanned::Spanned;
-use syn::{parse_macro_input, AttributeArgs, Block, FnArg, ItemFn, Pat, PatType, Type};
+use syn::{parse_macro_input, AttributeArgs, Block, Expr, FnArg, ItemFn, Pat, PatType, Type};
 
 #[derive(Debug, FromMeta)]
 struct Args {
@@ -23,9 +23,29 @@ struct Usage {
 
 #[derive(Debug)]
 enum Argument {
-    Parameter { name: String, priority: usize },
-    OptionalParameter { name: String, priority: usize },
-    Literal { values: Vec<String> },
+    Parameter {
+        name: String,
+        priority: usize,
+    },
+    OptionalParameter {
+        name: String,
+        priority: usize,
+        /// Source text of the expression to fall back to when the
+        /// argument is missing from the input, e.g. `[count=1]` => `Some("1")`.
+        default: Option<String>,
+    },
+    Literal {
+        values: Vec<String>,
+    },
+    /// A `--name` or `--name <param>` flag, matched anywhere in the input
+    /// that remains after the arguments before it in the usage; it is not
+    /// found if it appears ahead of those arguments.
+    Flag {
+        name: String,
+        /// The name of the value parameter, for flags of the form
+        /// `--name <param>`. `None` for a boolean flag such as `--silent`.
+        parameter: Option<String>,
+    },
 }
 
 /// The set of function parameters which should be obtained
@@ -49,9 +69,7 @@ pub fn command(
         ),
     };
 
-    if let Some(asyncness) = input.sig.asyncness {
-        emit_error!(asyncness.span(), "command function may not be `async`");
-    }
+    let is_async = input.sig.asyncness.is_some();
 
     if let Some(first_generic) = input.sig.generics.params.iter().next() {
         let help = first_generic
@@ -95,6 +113,7 @@ pub fn command(
         ctx_type,
         &input.block,
         &provided_parameters,
+        is_async,
     );
     let visibility = &input.vis;
 
@@ -117,20 +136,61 @@ fn parse_usage(usage: &str) -> Usage {
     // Parse arguments by spaces. Each space-separared
     // string can have one of the following meanings:
     // <string>: a required, named parameter
+    // <string:priority>: a required, named parameter with an explicit
+    //                    priority used to order ambiguous matches (higher
+    //                    priority parsers are tried first); defaults to 0
     // [string]: an optional, named parameter
+    // [string=expr]: an optional, named parameter which falls back to
+    //                the expression `expr` when absent from the input
+    // --name: a boolean flag, matched anywhere in the input that
+    //         follows the arguments before it in the usage
+    // --name <param>: a flag taking a value, matched in the same way
     // literal|literal2...: one or more possible literal parameters
-    for splitted in usage.split(' ') {
+    let mut tokens = usage.split(' ').peekable();
+    while let Some(splitted) = tokens.next() {
+        if let Some(name) = splitted.strip_prefix("--") {
+            let parameter = match tokens.peek() {
+                Some(next) if next.starts_with('<') && next.ends_with('>') && next.len() >= 2 => {
+                    let param = &next[1..next.len() - 1];
+                    let parameter = param.to_owned();
+                    tokens.next();
+                    Some(parameter)
+                }
+                _ => None,
+            };
+            arguments.push(Argument::Flag {
+                name: name.to_owned(),
+                parameter,
+            });
+            continue;
+        }
+
         let (first, middle) = splitted.split_at(1.min(splitted.len()));
         let (middle, last) = middle.split_at(middle.len().saturating_sub(1));
         match (first, middle, last) {
-            ("<", param, ">") => arguments.push(Argument::Parameter {
-                name: param.to_owned(),
-                priority: 0,
-            }),
-            ("[", param, "]") => arguments.push(Argument::OptionalParameter {
-                name: param.to_owned(),
-                priority: 0,
-            }),
+            ("<", param, ">") => {
+                let (name, priority) = match param.rfind(':') {
+                    Some(index) => (
+                        param[..index].to_owned(),
+                        param[index + 1..].parse().unwrap_or_else(|_| {
+                            abort_call_site!("invalid priority in usage parameter `{}`", param)
+                        }),
+                    ),
+                    None => (param.to_owned(), 0),
+                };
+                arguments.push(Argument::Parameter { name, priority })
+            }
+            ("[", param, "]") => {
+                let (name, default) = match param.find('=') {
+                    Some(index) => (param[..index].to_owned(), Some(param[index + 1..].to_owned())),
+                    None => (param.to_owned(), None),
+                };
+                arguments.push(Argument::OptionalParameter {
+                    name,
+                    priority: 0,
+                    default,
+                })
+            }
             (_, _, _) => {
                 // Parse literals: individual values are separated by the pipe operator.
                 let values = splitted.split('|').map(String::from).collect::<Vec<_>>();
@@ -152,6 +212,9 @@ fn collect_parameters<'a>(
             Argument::Parameter { name, .. } | Argument::OptionalParameter { name, .. } => {
                 collect_parameter(name, &mut parameters, arg, inputs);
             }
+            Argument::Flag { name, parameter } => {
+                collect_parameter(parameter.as_deref().unwrap_or(name), &mut parameters, arg, inputs);
+            }
             Argument::Literal { .. } => (),
         }
     }
@@ -183,24 +246,56 @@ fn collect_parameter<'a>(
 fn validate_parameter(name: &str, arg: &Argument, arg_type: &PatType) {
     // If not an optional parameter, ensure the type is not an option.
     // Otherwise, ensure it _is_ an Option.
-    if let Argument::Parameter { .. } = arg {
-        // not optional
-        validate_argument_type(&arg_type.ty, name);
-        if let Type::Path(path) = arg_type.ty.as_ref() {
-            // verify that path is not an `Option`
-            if path.path.is_ident(&Ident::new("Option", Span::call_site())) {
+    match arg {
+        Argument::Parameter { .. } => {
+            // not optional
+            validate_argument_type(&arg_type.ty, name);
+            if is_ident_type(&arg_type.ty, "Option") {
                 emit_error!(
-                    path.span(), "the parameter {} is defined as an `Option`, but the usage message indicates it is a required argument", name;
+                    arg_type.ty.span(), "the parameter {} is defined as an `Option`, but the usage message indicates it is a required argument", name;
 
                     help = "change the usage instructions to make the argument optional: `<{}>`", name;
                 );
             }
-        };
-    } else {
-        // optional
+        }
+        Argument::OptionalParameter { default: Some(_), .. } => {
+            // optional with a default: since a value is always available
+            // (either parsed or defaulted), the parameter need not be wrapped
+            // in `Option`.
+        }
+        Argument::OptionalParameter { default: None, .. } => {
+            // optional, no default: any type is accepted, including `Option<T>`
+        }
+        Argument::Flag { parameter: Some(_), .. } => {
+            // `--name <param>`: a missing flag parses to `None`, so the
+            // handler parameter must be an `Option`.
+            if !is_ident_type(&arg_type.ty, "Option") {
+                emit_error!(
+                    arg_type.ty.span(), "the parameter {} is a flag's value, so it must be wrapped in `Option` since the flag itself may be absent", name;
+
+                    help = "change the type of the parameter {} to `Option<...>`", name;
+                );
+            }
+        }
+        Argument::Flag { parameter: None, .. } => {
+            // boolean flag, e.g. `--silent`: always parses to a `bool`
+            // indicating whether it was present.
+            if !is_ident_type(&arg_type.ty, "bool") {
+                emit_error!(
+                    arg_type.ty.span(), "the parameter {} is a boolean flag, so it must be typed `bool`", name;
+
+                    help = "change the type of the parameter {} to `bool`", name;
+                );
+            }
+        }
+        Argument::Literal { .. } => unreachable!("literals have no corresponding parameter"),
     }
 }
 
+fn is_ident_type(ty: &Type, ident: &str) -> bool {
+    matches!(ty, Type::Path(path) if path.path.is_ident(&Ident::new(ident, Span::call_site())))
+}
+
 fn validate_argument_type(ty: &Type, name: &str) {
     match ty {
         Type::ImplTrait(span) => emit_error!(
@@ -326,6 +421,7 @@ fn generate_command_spec(
     ctx_type: Option<(&Type, &Pat)>,
     block: &Block,
     provided: &ProvidedParameters,
+    is_async: bool,
 ) -> TokenStream {
     // let mut statements = vec![];
 
@@ -344,19 +440,53 @@ fn generate_command_spec(
     let mut i = 0;
     for argument in &usage.arguments {
         let argument = match argument {
-            Argument::Parameter { name, priority }
-            | Argument::OptionalParameter { name, priority } => {
+            Argument::Parameter { name, priority } => {
+                let argument_type = parameters[i];
+
+                let ty = &argument_type.ty;
+                i += 1;
+
+                quote! {
+                    lieutenant::Argument::Parser {
+                        name: #name.into(),
+                        satisfies: <#ty as lieutenant::ArgumentKind<#ctx_param>>::satisfies,
+                        complete: <#ty as lieutenant::ArgumentKind<#ctx_param>>::complete,
+                        argument_type: std::any::TypeId::of::<#ty>(),
+                        priority: #priority,
+                        // Missing from the input, not defaulted: the
+                        // command is dead-ended here, as it should be.
+                        optional: false,
+                    }
+                }
+            }
+            Argument::OptionalParameter {
+                name,
+                priority,
+                default,
+            } => {
                 let argument_type = parameters[i];
 
                 let ty = &argument_type.ty;
                 i += 1;
 
+                // A defaulted optional argument still needs the command to
+                // be reachable when the input runs out before it: when it
+                // belongs to the trailing run of omittable arguments,
+                // `register` attaches the command's executor to every node
+                // of that run and to the node just before it, so dispatch
+                // can fall back to the default instead of dead-ending. A
+                // defaulted argument followed by a required one gets no
+                // executor of its own.
+                let optional = default.is_some();
+
                 quote! {
                     lieutenant::Argument::Parser {
                         name: #name.into(),
                         satisfies: <#ty as lieutenant::ArgumentKind<#ctx_param>>::satisfies,
+                        complete: <#ty as lieutenant::ArgumentKind<#ctx_param>>::complete,
                         argument_type: std::any::TypeId::of::<#ty>(),
                         priority: #priority,
+                        optional: #optional,
                     }
                 }
             }
@@ -367,6 +497,37 @@ fn generate_command_spec(
                     }
                 }
             }
+            Argument::Flag { name, parameter } => {
+                let inner = match parameter {
+                    Some(_) => {
+                        let argument_type = parameters[i];
+                        let ty = &argument_type.ty;
+                        i += 1;
+
+                        quote! {
+                            Some(Box::new(lieutenant::Argument::Parser {
+                                name: #name.into(),
+                                satisfies: <#ty as lieutenant::ArgumentKind<#ctx_param>>::satisfies,
+                                complete: <#ty as lieutenant::ArgumentKind<#ctx_param>>::complete,
+                                argument_type: std::any::TypeId::of::<#ty>(),
+                                priority: 0,
+                                optional: false,
+                            }))
+                        }
+                    }
+                    None => {
+                        i += 1;
+                        quote! { None }
+                    }
+                };
+
+                quote! {
+                    lieutenant::Argument::Flag {
+                        name: #name.into(),
+                        inner: #inner,
+                    }
+                }
+            }
         };
 
         arguments.push(quote! {
@@ -382,6 +543,31 @@ fn generate_command_spec(
     let mut i = 0;
     for argument in usage.arguments.iter() {
         match argument {
+            Argument::OptionalParameter {
+                default: Some(default),
+                ..
+            } => {
+                let parameter = parameters[i];
+                let ident = &parameter.pat;
+                let ty = &parameter.ty;
+                let ctx_ident = match ctx_type {
+                    Some((_, ident)) => quote! { #ident },
+                    None => quote! { _ctx },
+                };
+                let default_expr: Expr = syn::parse_str(default).unwrap_or_else(|e| {
+                    abort_call_site!("invalid default expression `{}`: {}", default, e)
+                });
+
+                parse_args.push(quote! {
+                    let #ident = match <#ty as lieutenant::ArgumentKind<#ctx_param>>::parse(#ctx_ident, &mut #args_ident) {
+                        Ok(v) => v,
+                        Err(_) if #args_ident.is_empty() => #default_expr,
+                        Err(e) => return Err(e),
+                    };
+                });
+
+                i += 1;
+            }
             Argument::Parameter { .. } | Argument::OptionalParameter { .. } => {
                 let parameter = parameters[i];
                 let ident = &parameter.pat;
@@ -401,6 +587,37 @@ fn generate_command_spec(
                 let head = #args_ident.advance_until(" ");
                 debug_assert!([#(#values),*].contains(&head));
             }),
+            Argument::Flag { name, parameter: None } => {
+                let parameter = parameters[i];
+                let ident = &parameter.pat;
+
+                parse_args.push(quote! {
+                    let #ident = #args_ident.take_flag(#name).is_some();
+                });
+
+                i += 1;
+            }
+            Argument::Flag {
+                name,
+                parameter: Some(_),
+            } => {
+                let parameter = parameters[i];
+                let ident = &parameter.pat;
+                let ty = &parameter.ty;
+                let ctx_ident = match ctx_type {
+                    Some((_, ident)) => quote! { #ident },
+                    None => quote! { _ctx },
+                };
+
+                parse_args.push(quote! {
+                    let #ident = match #args_ident.take_flag(#name) {
+                        Some(mut flag_input) => Some(<#ty as lieutenant::ArgumentKind<#ctx_param>>::parse(#ctx_ident, &mut flag_input)?),
+                        None => None,
+                    };
+                });
+
+                i += 1;
+            }
         }
     }
 
@@ -424,6 +641,42 @@ fn generate_command_spec(
 
     let arguments_len = arguments.len();
 
+    // A synchronous command body runs as a plain `Exec`; an `async fn` body
+    // is boxed into a future instead and registered as an `AsyncExec`, so
+    // that integrations (e.g. chat bots) can `.await` inside the handler.
+    // `AsyncExec<C>`'s `Fn(&mut C, &str) -> Pin<Box<dyn Future<...> + Send>>`
+    // is higher-ranked over the lifetime of its arguments rather than
+    // `'static`, so the returned future is allowed to (and here does) hold
+    // onto `ctx` across the handler body's `.await` points.
+    let (exec, async_exec) = if is_async {
+        (
+            quote! { None },
+            quote! {
+                Some(std::sync::Arc::new(move |#ctx_type, #args_ident| {
+                    let #args_ident = #args_ident.to_owned();
+                    Box::pin(async move {
+                        let mut #args_ident = lieutenant::Input::new(&#args_ident);
+                        use lieutenant::{ArgumentKind as _, Provider as _};
+                        #(#parse_args)*
+                        #block
+                    })
+                }))
+            },
+        )
+    } else {
+        (
+            quote! {
+                Some(|#ctx_type, #args_ident| {
+                    let mut #args_ident = lieutenant::Input::new(#args_ident);
+                    use lieutenant::{ArgumentKind as _, Provider as _};
+                    #(#parse_args)*
+                    #block
+                })
+            },
+            quote! { None },
+        )
+    };
+
     let res = quote! {
         let mut arguments = Vec::with_capacity(#arguments_len);
         #(#arguments)*
@@ -431,12 +684,8 @@ fn generate_command_spec(
         lieutenant::CommandSpec {
             arguments,
             description: #description,
-            exec: |#ctx_type, #args_ident| {
-                let mut #args_ident = lieutenant::Input::new(#args_ident);
-                use lieutenant::{ArgumentKind as _, Provider as _};
-                #(#parse_args)*
-                #block
-            },
+            exec: #exec,
+            async_exec: #async_exec,
         }
     };
     res
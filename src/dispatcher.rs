@@ -1,4 +1,7 @@
-use crate::{command::Exec, Argument, Command, CommandSpec, Context, Input};
+use crate::{
+    command::{AsyncExec, Exec},
+    Argument, Command, CommandSpec, Context, Input,
+};
 use slab::Slab;
 use smallvec::SmallVec;
 
@@ -11,6 +14,26 @@ pub enum RegisterError {
     ExecutableRoot,
 }
 
+#[derive(Debug)]
+pub enum DispatchError<C: Context> {
+    /// Either no registered command matched the input and the walk hit no
+    /// dead-end literal, or every matching command's executor failed; holds
+    /// the errors those executors returned (empty in the first case).
+    NoMatch(Vec<C::Error>),
+    /// An input token didn't match any literal at a dead end in the
+    /// command graph. If a similarly-spelled literal was registered at
+    /// that point, it's offered here as a "did you mean" suggestion.
+    UnknownLiteral {
+        /// The token from the input that failed to match.
+        token: String,
+        /// Byte offset of `token` within the original input.
+        offset: usize,
+        /// The closest registered literal at that point, if any fell
+        /// within the edit-distance threshold.
+        suggestion: Option<String>,
+    },
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct NodeKey(usize);
 
@@ -54,6 +77,7 @@ where
         let mut arguments = spec.arguments.iter().peekable();
 
         let mut node_key: Option<NodeKey> = None;
+        let mut chain: Vec<NodeKey> = Vec::with_capacity(spec.arguments.len());
 
         'argument: while let Some(argument) = arguments.peek() {
             let children = match node_key {
@@ -67,6 +91,7 @@ where
                 if argument == &&child.argument {
                     arguments.next();
                     node_key = Some(child_key);
+                    chain.push(child_key);
                     continue 'argument;
                 }
             }
@@ -85,16 +110,36 @@ where
             }
 
             node_key = Some(child_key);
+            chain.push(child_key);
         }
 
-        if let Some(key) = node_key {
-            let node = &mut self.nodes[key.0];
-            node.execs.push(spec.exec);
-        } else {
+        if chain.is_empty() {
             // Command with zero arguments?
             return Err(RegisterError::ExecutableRoot);
         }
 
+        // The command is reachable not just by supplying every argument
+        // (the last node in `chain`), but also by omitting some or all of
+        // a trailing run of omittable arguments (optional parameters that
+        // carry a default expression, and flags): once the input runs out
+        // the walk stops before them, so without this it would never reach
+        // a node with an attached executor. Attach the executor at every
+        // node from the start of that run onward (including the node just
+        // before it, which represents supplying none of the trailing run).
+        let first_reachable = trailing_optional_run_start(&spec.arguments)
+            .map(|start| start.saturating_sub(1))
+            .unwrap_or_else(|| chain.len() - 1);
+
+        for &key in &chain[first_reachable..] {
+            let node = &mut self.nodes[key.0];
+            if let Some(exec) = spec.exec {
+                node.execs.push(exec);
+            }
+            if let Some(async_exec) = spec.async_exec.clone() {
+                node.async_execs.push(async_exec);
+            }
+        }
+
         self.commands.push(spec);
 
         Ok(())
@@ -114,46 +159,295 @@ where
     }
 
     /// Dispatches a command. Returns whether a command was executed.
-    pub fn dispatch(&self, ctx: &mut C, command: &str) -> Result<C::Ok, Vec<C::Error>> {
-        let mut nodes = Vec::new();
+    ///
+    /// If multiple registered commands match `command` (e.g. a concrete
+    /// `<int>` argument alongside a catch-all `<greedy_string>`), every
+    /// matching executor is collected and they're tried in descending
+    /// order of the full root-to-leaf sequence of [`Argument::Parser`]
+    /// `priority`s along each match, so resolution is deterministic rather
+    /// than depending on registration order, and a higher-priority parser
+    /// earlier in the path still wins even when the branches converge on a
+    /// shared, equal-priority tail. Literal and flag matches, being exact,
+    /// are always tried first.
+    pub fn dispatch(&self, ctx: &mut C, command: &str) -> Result<C::Ok, DispatchError<C>> {
         let mut errors = Vec::new();
+        let (terminals, dead_end) = self.walk(ctx, command);
+
+        let mut candidates: Vec<(Vec<usize>, &Exec<C>)> = Vec::new();
+        for (priority_path, node_key) in &terminals {
+            for exec in &self.nodes[node_key.0].execs {
+                candidates.push((priority_path.clone(), exec));
+            }
+        }
+
+        if candidates.is_empty() {
+            return Err(Self::no_match(errors, dead_end));
+        }
+
+        candidates.sort_by(|(a, _), (b, _)| b.cmp(a));
+        for (_, exec) in candidates {
+            match exec(ctx, command) {
+                Ok(ok) => return Ok(ok),
+                Err(err) => errors.push(err),
+            }
+        }
+        Err(DispatchError::NoMatch(errors))
+    }
+
+    /// Dispatches a command whose executor may be asynchronous.
+    ///
+    /// Mirrors [`dispatch`](Self::dispatch), including its priority-based
+    /// resolution and "did you mean ...?" suggestions, but also considers
+    /// commands registered through `async fn` handlers, awaiting each
+    /// candidate in turn.
+    pub async fn dispatch_async(&self, ctx: &mut C, command: &str) -> Result<C::Ok, DispatchError<C>> {
+        let mut errors = Vec::new();
+        let (terminals, dead_end) = self.walk(ctx, command);
+
+        let mut candidates: Vec<(Vec<usize>, Candidate<C>)> = Vec::new();
+        for (priority_path, node_key) in &terminals {
+            let node = &self.nodes[node_key.0];
+            for exec in &node.execs {
+                candidates.push((priority_path.clone(), Candidate::Sync(exec)));
+            }
+            for async_exec in &node.async_execs {
+                candidates.push((priority_path.clone(), Candidate::Async(async_exec)));
+            }
+        }
+
+        if candidates.is_empty() {
+            return Err(Self::no_match(errors, dead_end));
+        }
+
+        candidates.sort_by(|(a, _), (b, _)| b.cmp(a));
+        for (_, candidate) in candidates {
+            let result = match candidate {
+                Candidate::Sync(exec) => exec(ctx, command),
+                // `AsyncExec<C>`'s future borrows `ctx` for its own
+                // lifetime rather than requiring `'static`, the same way
+                // `satisfies`/`complete` borrow it in `Argument::Parser`.
+                Candidate::Async(exec) => exec(ctx, command).await,
+            };
+            match result {
+                Ok(ok) => return Ok(ok),
+                Err(err) => errors.push(err),
+            }
+        }
+        Err(DispatchError::NoMatch(errors))
+    }
+
+    /// Shared graph walk used by both [`dispatch`](Self::dispatch) and
+    /// [`dispatch_async`](Self::dispatch_async): matches `command` against
+    /// every branch of the command graph and returns every reachable
+    /// terminal node together with the priority path that led to it (see
+    /// the doc comment on `dispatch` for what that path means), plus the
+    /// furthest dead-end literal mismatch, if any, for a "did you mean
+    /// ...?" suggestion.
+    fn walk(
+        &self,
+        ctx: &mut C,
+        command: &str,
+    ) -> (Vec<(Vec<usize>, NodeKey)>, Option<(usize, String, Vec<String>)>) {
+        let mut nodes = Vec::new();
+        let mut terminals = Vec::new();
+        // The dead-end literal mismatch that got furthest into the input,
+        // used to produce a "did you mean ...?" suggestion if nothing
+        // else matches.
+        let mut dead_end: Option<(usize, String, Vec<String>)> = None;
 
         for child_key in &self.children {
-            nodes.push((Input::new(command), child_key.0));
+            nodes.push((Input::new(command), child_key.0, Vec::new()));
         }
 
-        while let Some((mut input, node_key)) = nodes.pop() {
+        while let Some((mut input, node_key, priority_path)) = nodes.pop() {
             let node = &self.nodes[node_key];
-            let satisfies = match &node.argument {
+            let (satisfies, priority) = match &node.argument {
                 Argument::Literal { values } => {
+                    let offset = input.offset();
                     let parsed = input.advance_until(" ");
-                    values.iter().any(|value| value == parsed)
+                    let matched = values.iter().any(|value| value == parsed);
+                    if !matched {
+                        // Gather the literal values of every sibling dead
+                        // end at the furthest offset reached, rather than
+                        // keeping only whichever node the (LIFO, so
+                        // unordered) walk happened to visit last.
+                        match dead_end.as_mut() {
+                            Some((dead_offset, _, siblings)) if offset == *dead_offset => {
+                                siblings.extend(values.iter().map(|value| value.to_string()));
+                            }
+                            Some((dead_offset, dead_token, siblings)) if offset > *dead_offset => {
+                                *dead_offset = offset;
+                                *dead_token = parsed.to_owned();
+                                *siblings = values.iter().map(|value| value.to_string()).collect();
+                            }
+                            Some(_) => {}
+                            None => {
+                                let siblings = values.iter().map(|value| value.to_string()).collect();
+                                dead_end = Some((offset, parsed.to_owned(), siblings));
+                            }
+                        }
+                    }
+                    (matched, usize::MAX)
+                }
+                Argument::Parser { satisfies, priority, .. } => {
+                    (satisfies(ctx, &mut input), *priority)
                 }
-                Argument::Parser { satisfies, .. } => satisfies(ctx, &mut input),
+                // Flags aren't positional: they can appear anywhere in the
+                // remaining input, so look for `--name` (and its value, if
+                // any) instead of consuming the next token in place.
+                // A flag that's absent still satisfies its node: flags are
+                // optional by nature (the generated parse_args binds a
+                // missing one to `bool`'s `false`/`Option<T>`'s `None`), so
+                // the walk must still be able to reach the terminal exec.
+                Argument::Flag { name, inner } => match input.take_flag(name) {
+                    Some(mut value) => match inner.as_deref() {
+                        Some(Argument::Parser { satisfies, .. }) => {
+                            (satisfies(ctx, &mut value), usize::MAX)
+                        }
+                        _ => (true, usize::MAX),
+                    },
+                    None => (true, usize::MAX),
+                },
             };
 
+            let mut priority_path = priority_path;
+            priority_path.push(priority);
+
             if input.is_empty() && satisfies {
-                for exec in &node.execs {
-                    match exec(ctx, command) {
-                        Ok(ok) => return Ok(ok),
-                        Err(err) => errors.push(err),
-                    }
-                }
+                terminals.push((priority_path, NodeKey(node_key)));
                 continue;
             }
 
             if satisfies {
                 for child_key in &node.children {
-                    nodes.push((input, child_key.0));
+                    nodes.push((input.clone(), child_key.0, priority_path.clone()));
                 }
             }
         }
-        Err(errors)
+
+        (terminals, dead_end)
+    }
+
+    /// Builds the error returned when no candidate matched: `UnknownLiteral`
+    /// if the walk hit a dead-end literal (carrying a "did you mean ...?"
+    /// suggestion if a registered literal there was close enough),
+    /// otherwise `NoMatch` with `errors`.
+    fn no_match(
+        errors: Vec<C::Error>,
+        dead_end: Option<(usize, String, Vec<String>)>,
+    ) -> DispatchError<C> {
+        if let Some((offset, token, siblings)) = dead_end {
+            let suggestion = closest_literal(&token, siblings.iter().map(String::as_str));
+            return DispatchError::UnknownLiteral {
+                token,
+                offset,
+                suggestion,
+            };
+        }
+        DispatchError::NoMatch(errors)
     }
 
     pub fn commands(&self) -> impl Iterator<Item = &CommandSpec<C>> {
         self.commands.iter()
     }
+
+    /// Returns the set of completions available for `partial`, a command
+    /// string which may be truncated mid-argument. Useful for powering
+    /// tab-completion in an interactive front-end.
+    pub fn suggestions(&self, ctx: &mut C, partial: &str) -> Vec<String> {
+        let mut nodes = Vec::new();
+        let mut suggestions = Vec::new();
+
+        for child_key in &self.children {
+            nodes.push((Input::new(partial), child_key.0));
+        }
+
+        while let Some((mut input, node_key)) = nodes.pop() {
+            let node = &self.nodes[node_key];
+
+            if input.is_empty() {
+                // Nothing left to type before reaching `node` itself: it's
+                // `node`, not its children, that describes what comes next.
+                collect_suggestions(node, &*ctx, &mut suggestions);
+                continue;
+            }
+
+            let satisfies = match &node.argument {
+                Argument::Literal { values } => {
+                    let parsed = input.advance_until(" ");
+                    if input.is_empty() {
+                        // The final token: every literal with `parsed` as a
+                        // prefix is a completion candidate.
+                        suggestions.extend(
+                            values
+                                .iter()
+                                .filter(|value| value.starts_with(parsed))
+                                .map(|value| value.to_string()),
+                        );
+                        false
+                    } else {
+                        values.iter().any(|value| value == parsed)
+                    }
+                }
+                Argument::Parser { satisfies, complete, .. } => {
+                    let partial = input.clone();
+                    let ok = satisfies(ctx, &mut input);
+                    if input.is_empty() {
+                        // Either fully parsed with nothing left to type, or
+                        // this was the final, partially-typed token: either
+                        // way, complete against what was actually typed
+                        // here rather than an empty string.
+                        if let Some(complete) = complete {
+                            suggestions.extend(complete(&*ctx, &partial));
+                        }
+                    }
+                    ok
+                }
+                // As in `dispatch`, an absent flag still satisfies its node.
+                Argument::Flag { name, inner } => match input.take_flag(name) {
+                    Some(mut value) => match inner.as_deref() {
+                        Some(Argument::Parser { satisfies, .. }) => satisfies(ctx, &mut value),
+                        _ => true,
+                    },
+                    None => true,
+                },
+            };
+
+            if satisfies {
+                for child_key in &node.children {
+                    nodes.push((input.clone(), child_key.0));
+                }
+            }
+        }
+
+        suggestions.sort();
+        suggestions.dedup();
+        suggestions
+    }
+}
+
+/// Collects the suggestions contributed by a single node for
+/// [`CommandDispatcher::suggestions`]: every literal value for a literal
+/// node, or the result of its `complete` callback for a parser node.
+fn collect_suggestions<C: Context>(node: &Node<C>, ctx: &C, suggestions: &mut Vec<String>) {
+    match &node.argument {
+        Argument::Literal { values } => {
+            suggestions.extend(values.iter().map(|value| value.to_string()))
+        }
+        Argument::Parser { complete, .. } => {
+            if let Some(complete) = complete {
+                suggestions.extend(complete(ctx, &Input::new("")));
+            }
+        }
+        Argument::Flag { name, .. } => suggestions.push(format!("--{}", name)),
+    }
+}
+
+/// A matching executor awaiting a final priority-ordered attempt in
+/// [`CommandDispatcher::dispatch_async`].
+enum Candidate<'a, C: Context> {
+    Sync(&'a Exec<C>),
+    Async(&'a AsyncExec<C>),
 }
 
 /// Node on the command graph.
@@ -161,6 +455,7 @@ pub struct Node<C: Context> {
     children: SmallVec<[NodeKey; 4]>,
     argument: Argument<C>,
     execs: Vec<Exec<C>>,
+    async_execs: Vec<AsyncExec<C>>,
 }
 
 impl<C: Context> From<Argument<C>> for Node<C> {
@@ -169,10 +464,63 @@ impl<C: Context> From<Argument<C>> for Node<C> {
             children: Default::default(),
             argument,
             execs: Vec::new(),
+            async_execs: Vec::new(),
         }
     }
 }
 
+/// Returns the start index of the trailing run of `arguments` made up
+/// entirely of defaulted optional parameters (`Argument::Parser` with
+/// `optional: true`) and flags (`Argument::Flag`), or `None` if there's no
+/// such run.
+fn trailing_optional_run_start<C: Context>(arguments: &[Argument<C>]) -> Option<usize> {
+    let mut start = arguments.len();
+    for (i, argument) in arguments.iter().enumerate().rev() {
+        match argument {
+            Argument::Parser { optional: true, .. } | Argument::Flag { .. } => start = i,
+            _ => break,
+        }
+    }
+
+    if start == arguments.len() {
+        None
+    } else {
+        Some(start)
+    }
+}
+
+/// Finds the literal in `candidates` closest to `token` by edit distance,
+/// provided one is within a reasonable typo threshold.
+fn closest_literal<'a>(token: &str, candidates: impl Iterator<Item = &'a str>) -> Option<String> {
+    let threshold = 1.max(token.len() / 3);
+    candidates
+        .map(|candidate| (candidate, levenshtein(candidate, token)))
+        .filter(|(_, distance)| *distance <= threshold)
+        .min_by_key(|(_, distance)| *distance)
+        .map(|(candidate, _)| candidate.to_owned())
+}
+
+/// Computes the Levenshtein edit distance between `a` and `b`.
+fn levenshtein(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut cur = vec![0; b.len() + 1];
+
+    for (i, &a_char) in a.iter().enumerate() {
+        cur[0] = i + 1;
+        for (j, &b_char) in b.iter().enumerate() {
+            cur[j + 1] = (prev[j + 1] + 1)
+                .min(cur[j] + 1)
+                .min(prev[j] + (a_char != b_char) as usize);
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+
+    prev[b.len()]
+}
+
 #[cfg(test)]
 mod tests {
     /*use super::*;